@@ -0,0 +1,253 @@
+//! A bounded, request-coalescing async-memoization cache built on `Shared`.
+//!
+//! An `Asyncmemo` maps a key `K` to an in-flight-or-completed future produced
+//! by a user-supplied filler. Concurrent `get` calls for the same key share a
+//! single underlying computation through this crate's `Shared` combinator, so
+//! a value is only computed once no matter how many callers ask for it at the
+//! same time.
+//!
+//! # Examples
+//!
+//! ```
+//! use futures::future::*;
+//! use futures::asyncmemo::{Asyncmemo, Weight};
+//!
+//! struct Doubled(i32);
+//!
+//! impl Weight for Doubled {
+//!     fn weight(&self) -> usize { 4 }
+//! }
+//!
+//! let memo = Asyncmemo::with_limits(|k: i32| ok::<_, bool>(Doubled(k * 2)), 16, 1024);
+//! let a = memo.get(3);
+//! let b = memo.get(3); // dedupes onto the same computation as `a`
+//! assert_eq!(6, a.wait().unwrap().0);
+//! assert_eq!(6, b.wait().unwrap().0);
+//! ```
+
+use std::hash::Hash;
+use std::sync::{Arc, Mutex};
+
+use linked_hash_map::LinkedHashMap;
+
+use Future;
+use future::shared::Shared;
+
+/// The approximate weight of a cached value.
+///
+/// Weights are used together with a maximum entry count to bound the amount of
+/// memory an `Asyncmemo` retains. A value's weight is usually its approximate
+/// heap size.
+pub trait Weight {
+    /// Returns the approximate weight of this value.
+    fn weight(&self) -> usize;
+}
+
+/// A bounded async-memoization cache.
+///
+/// See the [module level documentation](index.html) for details. Clones of an
+/// `Asyncmemo` share the same underlying cache.
+pub struct Asyncmemo<K, F, Fill>
+    where F: Future
+{
+    inner: Arc<Inner<K, F, Fill>>,
+}
+
+struct Inner<K, F, Fill>
+    where F: Future
+{
+    /// Constructs the future for a key not already present in the cache.
+    filler: Fill,
+    /// Maximum number of entries retained before least-recently-used eviction.
+    max_entries: usize,
+    /// Maximum total weight of completed entries before eviction.
+    max_weight: usize,
+    /// The cache itself, in least- to most-recently-used order.
+    map: Mutex<LinkedHashMap<K, Shared<F>>>,
+}
+
+impl<K, F, Fill> Asyncmemo<K, F, Fill>
+    where K: Clone + Eq + Hash,
+          F: Future,
+          F::Item: Weight,
+          Fill: Fn(K) -> F
+{
+    /// Creates a new `Asyncmemo` filled by `filler`, bounded to at most
+    /// `max_entries` entries and `max_weight` total weight of completed values.
+    pub fn with_limits(filler: Fill, max_entries: usize, max_weight: usize) -> Self {
+        Asyncmemo {
+            inner: Arc::new(Inner {
+                filler: filler,
+                max_entries: max_entries,
+                max_weight: max_weight,
+                map: Mutex::new(LinkedHashMap::new()),
+            }),
+        }
+    }
+
+    /// Returns a `Shared` future for `key`.
+    ///
+    /// If a matching entry already exists it is marked most-recently-used and a
+    /// clone is returned, so concurrent callers dedupe onto a single
+    /// computation. A completed entry whose future failed is evicted first so
+    /// that the next `get` retries rather than replaying the error.
+    pub fn get(&self, key: K) -> Shared<F> {
+        let mut map = self.inner.map.lock().unwrap();
+
+        let retry = match map.get_refresh(&key) {
+            Some(shared) => {
+                match shared.peek() {
+                    // A cached failure should not be replayed: drop it and
+                    // recompute below.
+                    Some(Err(_)) => true,
+                    _ => return shared.clone(),
+                }
+            }
+            None => false,
+        };
+        if retry {
+            map.remove(&key);
+        }
+
+        let shared = Shared::new((self.inner.filler)(key.clone()));
+        map.insert(key, shared.clone());
+        self.evict(&mut map);
+        shared
+    }
+
+    /// Evicts least-recently-used completed entries until both the entry-count
+    /// and total-weight bounds hold. In-flight entries are never evicted and do
+    /// not count toward the weight bound until they resolve.
+    fn evict(&self, map: &mut LinkedHashMap<K, Shared<F>>) {
+        // Weigh the cache once, then keep the running total up to date as
+        // victims are removed rather than rescanning every iteration.
+        let mut total_weight = map.values()
+            .filter_map(|shared| shared.peek())
+            .filter_map(|result| result.ok())
+            .map(|item| item.weight())
+            .fold(0usize, |acc, w| acc + w);
+
+        while map.len() > self.inner.max_entries || total_weight > self.inner.max_weight {
+            // Find the least-recently-used entry that has already resolved,
+            // along with the weight it contributes (none for a failure).
+            let victim = map.iter()
+                .filter_map(|(key, shared)| {
+                    shared.peek().map(|result| {
+                        let weight = result.ok().map_or(0, |item| item.weight());
+                        (key.clone(), weight)
+                    })
+                })
+                .next();
+
+            match victim {
+                Some((key, weight)) => {
+                    map.remove(&key);
+                    total_weight -= weight;
+                }
+                // Only in-flight entries remain; nothing can be evicted yet.
+                None => break,
+            }
+        }
+    }
+}
+
+impl<K, F, Fill> Clone for Asyncmemo<K, F, Fill>
+    where F: Future
+{
+    fn clone(&self) -> Self {
+        Asyncmemo { inner: self.inner.clone() }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::sync::Arc;
+    use std::sync::atomic::{AtomicUsize, Ordering};
+
+    use {Future, Poll, Async};
+
+    impl Weight for u32 {
+        fn weight(&self) -> usize {
+            1
+        }
+    }
+
+    /// A future that resolves immediately to a fixed value every time it is
+    /// polled.
+    struct Filled(u32);
+
+    impl Future for Filled {
+        type Item = u32;
+        type Error = bool;
+
+        fn poll(&mut self) -> Poll<u32, bool> {
+            Ok(Async::Ready(self.0))
+        }
+    }
+
+    /// A future that fails immediately.
+    struct Failed;
+
+    impl Future for Failed {
+        type Item = u32;
+        type Error = bool;
+
+        fn poll(&mut self) -> Poll<u32, bool> {
+            Err(true)
+        }
+    }
+
+    #[test]
+    fn get_dedupes_onto_one_computation() {
+        let calls = Arc::new(AtomicUsize::new(0));
+        let counter = calls.clone();
+        let memo = Asyncmemo::with_limits(move |key: u32| {
+            counter.fetch_add(1, Ordering::SeqCst);
+            Filled(key)
+        }, 16, 1024);
+
+        let first = memo.get(1);
+        let _second = memo.get(1);
+        // The second `get` reuses the in-flight entry rather than filling again.
+        assert_eq!(1, calls.load(Ordering::SeqCst));
+        assert_eq!(1, *first.wait().unwrap());
+    }
+
+    #[test]
+    fn evicts_least_recently_used_completed_entry() {
+        let calls = Arc::new(AtomicUsize::new(0));
+        let counter = calls.clone();
+        let memo = Asyncmemo::with_limits(move |key: u32| {
+            counter.fetch_add(1, Ordering::SeqCst);
+            Filled(key)
+        }, 2, 1024);
+
+        // Resolve each entry so it becomes evictable.
+        assert_eq!(1, *memo.get(1).wait().unwrap());
+        assert_eq!(2, *memo.get(2).wait().unwrap());
+        assert_eq!(3, *memo.get(3).wait().unwrap());
+        assert_eq!(3, calls.load(Ordering::SeqCst));
+
+        // Inserting key 3 pushed the cache over its two-entry bound, evicting
+        // the least-recently-used completed entry (key 1), so fetching it again
+        // re-runs the filler.
+        assert_eq!(1, *memo.get(1).wait().unwrap());
+        assert_eq!(4, calls.load(Ordering::SeqCst));
+    }
+
+    #[test]
+    fn failed_entries_are_retried() {
+        let calls = Arc::new(AtomicUsize::new(0));
+        let counter = calls.clone();
+        let memo = Asyncmemo::with_limits(move |_key: u32| {
+            counter.fetch_add(1, Ordering::SeqCst);
+            Failed
+        }, 16, 1024);
+
+        assert!(memo.get(1).wait().is_err());
+        assert!(memo.get(1).wait().is_err());
+        // The failure is not cached, so the second `get` fills again.
+        assert_eq!(2, calls.load(Ordering::SeqCst));
+    }
+}
@@ -14,15 +14,22 @@
 //! ```
 
 use std::mem;
-use std::vec::Vec;
-use std::sync::{Arc, RwLock};
+use std::ptr;
+use std::usize;
+use std::sync::{Arc, Weak, RwLock};
 use std::sync::atomic::{AtomicBool, Ordering};
 use std::ops::Deref;
 
+use slab::Slab;
+
 use {Future, Poll, Async};
 use task::{self, Task};
 use lock::Lock;
 
+/// Sentinel `waker_key` for a `Shared` clone that has not yet registered a
+/// waiting task in the slab.
+const WAKER_KEY_NONE: usize = usize::MAX;
+
 
 /// A future that is cloneable and can be polled in multiple threads.
 /// Use Future::shared() method to convert any future into a `Shared` future.
@@ -31,6 +38,9 @@ pub struct Shared<F>
     where F: Future
 {
     inner: Arc<Inner<F>>,
+    /// This clone's slot in the waiter slab, or `WAKER_KEY_NONE` if it has not
+    /// parked a task yet.
+    waker_key: usize,
 }
 
 struct Inner<F>
@@ -48,7 +58,7 @@ struct Inner<F>
 /// 1. Done - contains the result of the original future.
 /// 2. Waiting - contains the waiting tasks.
 enum State<T, E> {
-    Waiting(Vec<Task>),
+    Waiting(Slab<Option<Task>>),
     Done(Result<SharedItem<T>, SharedError<E>>),
 }
 
@@ -61,8 +71,9 @@ impl<F> Shared<F>
             inner: Arc::new(Inner {
                 original_future: Lock::new(Some(future)),
                 result_ready: AtomicBool::new(false),
-                state: RwLock::new(State::Waiting(vec![])),
+                state: RwLock::new(State::Waiting(Slab::new())),
             }),
+            waker_key: WAKER_KEY_NONE,
         }
     }
 
@@ -86,8 +97,10 @@ impl<F> Shared<F>
             State::Waiting(waiters) => {
                 drop(state);
                 self.inner.result_ready.store(true, Ordering::Relaxed);
-                for task in waiters {
-                    task.unpark();
+                for (_, waiter) in waiters {
+                    if let Some(task) = waiter {
+                        task.unpark();
+                    }
                 }
             }
             State::Done(_) => panic!("store_result() was called twice"),
@@ -95,6 +108,164 @@ impl<F> Shared<F>
 
         result.map(Async::Ready)
     }
+
+    /// Returns the result of the shared future if it is already resolved,
+    /// without polling the original future or parking a task.
+    ///
+    /// Returns `Some` once the result is ready, and `None` otherwise. This is
+    /// useful for diagnostics and fast paths that want an already-computed
+    /// result but must not block or register interest when it isn't ready yet.
+    pub fn peek(&self) -> Option<Result<SharedItem<F::Item>, SharedError<F::Error>>> {
+        if self.inner.result_ready.load(Ordering::Relaxed) {
+            match *self.inner.state.read().unwrap() {
+                State::Done(ref result) => Some(result.clone()),
+                State::Waiting(_) => unreachable!(),
+            }
+        } else {
+            None
+        }
+    }
+
+    /// Attempts to take ownership of the computed result.
+    ///
+    /// Succeeds only when this is the sole strong `Shared` handle, its result
+    /// is ready, and no `SharedItem`/`SharedError` clone of that result is
+    /// still held elsewhere, in which case the owned `F::Item`/`F::Error` is
+    /// returned without the extra `Arc` clone that `SharedItem`/`SharedError`
+    /// impose. Otherwise the `Shared` is handed back unchanged.
+    pub fn try_unwrap(self) -> Result<Result<F::Item, F::Error>, Shared<F>> {
+        // Bail out cheaply, and crucially without disturbing `self.inner`, if
+        // this clearly isn't the reclaim case: the result isn't ready, or other
+        // strong `Shared` handles still exist. Rebuilding the `Arc` here would
+        // change its identity and silently invalidate any `WeakShared`.
+        if !self.inner.result_ready.load(Ordering::Relaxed) ||
+           Arc::strong_count(&self.inner) != 1 {
+            return Err(self);
+        }
+
+        // `Arc::try_unwrap` on `inner` proves we hold the only `Shared`, but the
+        // payload `Arc<T>`/`Arc<E>` can still be shared: `store_result` keeps a
+        // copy in `State::Done`, and `poll`/`peek`/`read_result` hand out more.
+        // If any such clone is outstanding we cannot move the value out, so hand
+        // the handle back rather than cloning or panicking.
+        {
+            let state = self.inner.state.read().unwrap();
+            let unique = match *state {
+                State::Done(Ok(ref item)) => Arc::strong_count(&item.item) == 1,
+                State::Done(Err(ref error)) => Arc::strong_count(&error.error) == 1,
+                State::Waiting(_) => false,
+            };
+            if !unique {
+                drop(state);
+                return Err(self);
+            }
+        }
+
+        // Sole owner, result ready, payload uniquely held. Move the `Arc` out
+        // without running `Shared`'s destructor, which would otherwise try to
+        // vacate a slab slot we are about to consume.
+        let this = mem::ManuallyDrop::new(self);
+        let waker_key = this.waker_key;
+        let inner = unsafe { ptr::read(&this.inner) };
+        match Arc::try_unwrap(inner) {
+            Ok(inner) => {
+                let Inner { original_future, result_ready, state } = inner;
+                match state.into_inner().unwrap() {
+                    State::Done(result) => {
+                        // The read-lock checks above were dropped before this
+                        // point, so a racing `upgrade()`/`peek()` could have
+                        // cloned the payload `Arc` in the meantime. Re-check on
+                        // the now exclusively-owned `Inner`: move the value out
+                        // only if it is genuinely unique, otherwise rebuild the
+                        // handle and hand it back.
+                        match payload_into_inner(result) {
+                            Ok(result) => Ok(result),
+                            Err(result) => {
+                                Err(Shared {
+                                    inner: Arc::new(Inner {
+                                        original_future: original_future,
+                                        result_ready: result_ready,
+                                        state: RwLock::new(State::Done(result)),
+                                    }),
+                                    waker_key: waker_key,
+                                })
+                            }
+                        }
+                    }
+                    State::Waiting(_) => unreachable!(),
+                }
+            }
+            // Lost a race with a concurrent `WeakShared::upgrade`: another
+            // strong handle appeared. Rebuild from the same allocation and hand
+            // it back unchanged.
+            Err(inner) => {
+                Err(Shared {
+                    inner: inner,
+                    waker_key: waker_key,
+                })
+            }
+        }
+    }
+
+    /// Creates a new `WeakShared` handle to this shared future.
+    ///
+    /// The returned `WeakShared` holds a non-owning reference, so it does not
+    /// keep the underlying future or its result alive. It can be upgraded back
+    /// into a `Shared` with `WeakShared::upgrade` while at least one strong
+    /// `Shared` still exists.
+    pub fn downgrade(&self) -> WeakShared<F> {
+        WeakShared { inner: Arc::downgrade(&self.inner) }
+    }
+
+    /// Removes this clone's entry from the waiter slab, if it registered one.
+    fn unregister(&self) {
+        if self.waker_key == WAKER_KEY_NONE {
+            return;
+        }
+        if let Ok(mut state) = self.inner.state.write() {
+            if let State::Waiting(ref mut waiters) = *state {
+                if waiters.contains(self.waker_key) {
+                    waiters.remove(self.waker_key);
+                }
+            }
+        }
+    }
+}
+
+/// A weak reference to a `Shared` future.
+///
+/// Unlike `Shared`, a `WeakShared` does not keep the shared computation alive.
+/// Use `upgrade` to obtain a live `Shared` again, which succeeds only while at
+/// least one strong `Shared` still exists.
+pub struct WeakShared<F>
+    where F: Future
+{
+    inner: Weak<Inner<F>>,
+}
+
+impl<F> WeakShared<F>
+    where F: Future
+{
+    /// Attempts to upgrade this weak reference into a strong `Shared`.
+    ///
+    /// Returns `Some` if at least one strong `Shared` still exists, otherwise
+    /// `None`.
+    pub fn upgrade(&self) -> Option<Shared<F>> {
+        self.inner.upgrade().map(|inner| {
+            Shared {
+                inner: inner,
+                waker_key: WAKER_KEY_NONE,
+            }
+        })
+    }
+}
+
+impl<F> Clone for WeakShared<F>
+    where F: Future
+{
+    fn clone(&self) -> Self {
+        WeakShared { inner: self.inner.clone() }
+    }
 }
 
 impl<F> Future for Shared<F>
@@ -161,7 +332,14 @@ impl<F> Future for Shared<F>
         match state {
             &mut State::Done(ref result) => return result.clone().map(Async::Ready),
             &mut State::Waiting(ref mut waiters) => {
-                waiters.push(task::park());
+                if self.waker_key == WAKER_KEY_NONE {
+                    // First `NotReady` for this clone: register a fresh slot.
+                    self.waker_key = waiters.insert(Some(task::park()));
+                } else {
+                    // Already registered: refresh the existing slot in place so
+                    // the slab holds at most one entry per live clone.
+                    waiters[self.waker_key] = Some(task::park());
+                }
             }
         }
 
@@ -173,7 +351,41 @@ impl<F> Clone for Shared<F>
     where F: Future
 {
     fn clone(&self) -> Self {
-        Shared { inner: self.inner.clone() }
+        // Each clone registers its interest independently, so start with the
+        // sentinel key rather than inheriting the source's slab slot.
+        Shared {
+            inner: self.inner.clone(),
+            waker_key: WAKER_KEY_NONE,
+        }
+    }
+}
+
+impl<F> Drop for Shared<F>
+    where F: Future
+{
+    fn drop(&mut self) {
+        self.unregister();
+    }
+}
+
+/// Attempts to move the values out of a resolved payload when its `Arc`s are
+/// uniquely held. Returns `Ok` with the owned result, or `Err` with the intact
+/// payload when a clone is still outstanding (e.g. a racing `peek`).
+fn payload_into_inner<T, E>(result: Result<SharedItem<T>, SharedError<E>>)
+    -> Result<Result<T, E>, Result<SharedItem<T>, SharedError<E>>> {
+    match result {
+        Ok(item) => {
+            match Arc::try_unwrap(item.item) {
+                Ok(value) => Ok(Ok(value)),
+                Err(arc) => Err(Ok(SharedItem { item: arc })),
+            }
+        }
+        Err(error) => {
+            match Arc::try_unwrap(error.error) {
+                Ok(value) => Ok(Err(value)),
+                Err(arc) => Err(Err(SharedError { error: arc })),
+            }
+        }
     }
 }
 
@@ -229,4 +441,102 @@ impl<E> Deref for SharedError<E> {
     fn deref(&self) -> &E {
         &self.error.as_ref()
     }
-}
\ No newline at end of file
+}
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use {Future, Poll, Async};
+
+    /// A future that is ready immediately with a single value.
+    struct Ready(Option<u32>);
+
+    impl Future for Ready {
+        type Item = u32;
+        type Error = bool;
+
+        fn poll(&mut self) -> Poll<u32, bool> {
+            Ok(Async::Ready(self.0.take().expect("polled after completion")))
+        }
+    }
+
+    /// A future that never resolves.
+    struct Pending;
+
+    impl Future for Pending {
+        type Item = u32;
+        type Error = bool;
+
+        fn poll(&mut self) -> Poll<u32, bool> {
+            Ok(Async::NotReady)
+        }
+    }
+
+    #[test]
+    fn peek_is_none_until_resolved() {
+        let shared = Shared::new(Ready(Some(5)));
+        assert!(shared.peek().is_none());
+
+        assert_eq!(5, *shared.clone().wait().unwrap());
+
+        match shared.peek() {
+            Some(Ok(item)) => assert_eq!(5, *item),
+            _ => panic!("peek should observe the resolved value"),
+        }
+    }
+
+    #[test]
+    fn weak_upgrades_only_while_a_strong_handle_lives() {
+        let shared = Shared::new(Ready(Some(1)));
+        let weak = shared.downgrade();
+        assert!(weak.upgrade().is_some());
+
+        drop(shared);
+        assert!(weak.upgrade().is_none());
+    }
+
+    #[test]
+    fn try_unwrap_reclaims_sole_ready_handle() {
+        let shared = Shared::new(Ready(Some(7)));
+        assert_eq!(7, *shared.clone().wait().unwrap());
+
+        match shared.try_unwrap() {
+            Ok(Ok(value)) => assert_eq!(7, value),
+            _ => panic!("sole ready handle should be reclaimable"),
+        }
+    }
+
+    #[test]
+    fn try_unwrap_hands_back_when_item_is_retained() {
+        let shared = Shared::new(Ready(Some(9)));
+        let retained = shared.clone().wait().unwrap();
+
+        // The retained `SharedItem` keeps a second reference to the payload, so
+        // the value cannot be moved out: the handle comes back unchanged.
+        assert!(shared.try_unwrap().is_err());
+        assert_eq!(9, *retained);
+    }
+
+    #[test]
+    fn try_unwrap_hands_back_with_other_handles() {
+        let shared = Shared::new(Ready(Some(3)));
+        // Resolve first so that only the second handle prevents the reclaim.
+        assert_eq!(3, *shared.clone().wait().unwrap());
+        let _other = shared.clone();
+        assert!(shared.try_unwrap().is_err());
+    }
+
+    #[test]
+    fn try_unwrap_pending_keeps_weak_upgradable() {
+        let shared = Shared::new(Pending);
+        let weak = shared.downgrade();
+
+        let shared = match shared.try_unwrap() {
+            Err(shared) => shared,
+            Ok(_) => panic!("a pending future cannot be unwrapped"),
+        };
+
+        // Handing back must not reallocate `Inner`, or the weak handle breaks.
+        assert!(weak.upgrade().is_some());
+        drop(shared);
+    }
+}